@@ -0,0 +1,113 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// What an [`ApiKey`] is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+  Create,
+  Read,
+  Touch,
+}
+
+/// A configured API key, valid only within `[not_before, not_after)` and only for its
+/// granted scopes. The validity window is checked against the current time on every request,
+/// so keys with staggered windows rotate in and out without restarting the process; the key
+/// list itself is loaded once at startup.
+///
+/// Deserializable so a deployment can supply its key list as a JSON file (see
+/// `AppOpts::redirect_api_keys_file`) rather than compiling keys into the binary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+  pub key: String,
+  pub scopes: Vec<Scope>,
+  pub not_before: u64,
+  pub not_after: u64,
+}
+
+impl ApiKey {
+  fn is_valid_at(&self, now: u64) -> bool {
+    now >= self.not_before && now < self.not_after
+  }
+}
+
+/// Compares two byte strings in time proportional only to their length, not to how many
+/// leading bytes match.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  if a.len() != b.len() {
+    return false;
+  }
+  a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The set of configured keys, looked up by the raw key string presented by a caller.
+#[derive(Clone, Default)]
+pub struct KeyStore {
+  keys: HashMap<String, ApiKey>,
+}
+
+impl KeyStore {
+  pub fn new(keys: Vec<ApiKey>) -> Self {
+    Self {
+      keys: keys.into_iter().map(|k| (k.key.clone(), k)).collect(),
+    }
+  }
+
+  /// Rejects an unknown key, a key outside its validity window, or a key missing `scope`.
+  ///
+  /// Looks the presented key up by scanning the configured keys with a constant-time
+  /// comparison, rather than a `HashMap` lookup, so how long a comparison takes doesn't reveal
+  /// how many leading bytes of a real key a caller probing this auth boundary has guessed.
+  fn authorize(&self, presented_key: Option<&str>, scope: Scope) -> Result<(), StatusCode> {
+    let presented_key = presented_key.ok_or(StatusCode::UNAUTHORIZED)?;
+    let key = self
+      .keys
+      .values()
+      .find(|k| constant_time_eq(k.key.as_bytes(), presented_key.as_bytes()))
+      .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs();
+    if !key.is_valid_at(now) {
+      return Err(StatusCode::UNAUTHORIZED);
+    }
+    if !key.scopes.contains(&scope) {
+      return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+  }
+}
+
+/// Binds a [`KeyStore`] to the [`Scope`] a particular route group requires, so it can be
+/// handed to axum as middleware state via [`axum::middleware::from_fn_with_state`].
+#[derive(Clone)]
+pub struct ScopedAuth {
+  pub store: Arc<KeyStore>,
+  pub scope: Scope,
+}
+
+pub async fn require_scope(
+  State(ScopedAuth { store, scope }): State<ScopedAuth>,
+  headers: HeaderMap,
+  req: Request,
+  next: Next,
+) -> Response {
+  let presented_key = headers
+    .get(API_KEY_HEADER)
+    .and_then(|v| v.to_str().ok());
+
+  match store.authorize(presented_key, scope) {
+    Ok(()) => next.run(req).await,
+    Err(status) => status.into_response(),
+  }
+}
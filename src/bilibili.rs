@@ -0,0 +1,254 @@
+use crate::http_client::get_text_with_retry;
+use crate::resolver::{ResolvedMedia, VideoResolver};
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use moka::Expiry;
+use moka::future::Cache;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const COMMON_HEADERS: &[(&str, &str)] = &[
+  ("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7"),
+  ("Accept-Language", "zh-CN,zh;q=0.9"),
+  ("Cache-Control", "no-cache"),
+  ("DNT", "1"),
+  ("Pragma", "no-cache"),
+  ("Priority", "u=0, i"),
+  ("Sec-Fetch-Dest", "document"),
+  ("Sec-Fetch-Mode", "navigate"),
+  ("Sec-Fetch-Site", "none"),
+  ("Sec-Fetch-User", "?1"),
+  ("Upgrade-Insecure-Requests", "1"),
+];
+
+// https://www.bilibili.com/opus/400555526268551002
+// quality 120 = 4K
+// quality 116 = 1080P60
+// quality 112 = 1080P+
+// quality 80 = 1080P
+// quality 74 = 720P60
+// quality 64 = 720P
+// quality 32 = 480P
+// quality 16 = 360P
+const QUALITY_CODES: &[u32] = &[16, 32, 64, 74, 80, 112, 116, 120];
+
+// The page list (bvid -> cid) almost never changes for an already-published video, so we
+// cache it for a long time. Play URLs are short-lived signed links, so they get a custom
+// per-entry expiry derived from the `deadline=` timestamp embedded in the URL itself.
+const CID_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const PLAYURL_EXPIRY_SAFETY_MARGIN: Duration = Duration::from_secs(30);
+
+type PlayUrlKey = (String, usize, u32);
+
+struct PlayUrlExpiry;
+
+impl Expiry<PlayUrlKey, ResolvedMedia> for PlayUrlExpiry {
+  fn expire_after_create(
+    &self,
+    _key: &PlayUrlKey,
+    value: &ResolvedMedia,
+    _created_at: Instant,
+  ) -> Option<Duration> {
+    Some(playurl_ttl(&value.url))
+  }
+}
+
+fn playurl_ttl(url: &str) -> Duration {
+  let deadline = url
+    .split(['?', '&'])
+    .find_map(|kv| kv.strip_prefix("deadline="))
+    .and_then(|v| v.parse::<u64>().ok());
+
+  match deadline {
+    Some(deadline) => {
+      let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+      Duration::from_secs(deadline.saturating_sub(now)).saturating_sub(PLAYURL_EXPIRY_SAFETY_MARGIN)
+    }
+    // No deadline found (unexpected response shape): don't cache it for long.
+    None => Duration::from_secs(0),
+  }
+}
+
+/// Resolves Bilibili `BV`-prefixed ids to a playable media URL via the official API.
+pub struct BilibiliResolver {
+  client: reqwest::Client,
+  cid_cache: Cache<(String, usize), u64>,
+  playurl_cache: Cache<PlayUrlKey, ResolvedMedia>,
+}
+
+impl BilibiliResolver {
+  pub fn new(client: reqwest::Client) -> Self {
+    Self {
+      client,
+      cid_cache: Cache::builder().time_to_live(CID_CACHE_TTL).build(),
+      playurl_cache: Cache::builder().expire_after(PlayUrlExpiry).build(),
+    }
+  }
+
+  async fn resolve_cid(&self, bv: &str, p: usize) -> anyhow::Result<String> {
+    let url = format!("https://api.bilibili.com/x/player/pagelist?bvid={}", bv);
+    let strings = get_text_with_retry(&self.client, &url, COMMON_HEADERS).await?;
+    let cid = match serde_json::from_str::<serde_json::Value>(&strings) {
+      Ok(x) => x,
+      Err(_) => bail!("Failed to parse cid response: {}", &strings),
+    };
+
+    let cid = match cid
+      .as_object()
+      .and_then(|x| x.get("data"))
+      .and_then(|x| x.as_array())
+      .and_then(|x| x.get(p - 1))
+      .and_then(|x| x.as_object())
+      .and_then(|x| x.get("cid"))
+      .and_then(|x| x.as_number())
+    {
+      Some(x) => x.to_string(),
+      None => bail!("Failed to get cid from response: {}", cid),
+    };
+    Ok(cid)
+  }
+
+  async fn fetch_playurl(&self, bv: &str, cid: &str, quality: u32) -> anyhow::Result<ResolvedMedia> {
+    // fnval=16 asks Bilibili for a DASH manifest (data.dash.video[]/audio[]) in addition to
+    // the legacy progressive `durl` format, so modern/high-bitrate qualities resolve too.
+    let url = format!(
+      "https://api.bilibili.com/x/player/playurl?bvid={}&cid={}&qn={}&fnval=16&type=&otype=json&platform=html5&high_quality=1",
+      bv, cid, quality,
+    );
+    let strings = get_text_with_retry(&self.client, &url, COMMON_HEADERS).await?;
+    let json = match serde_json::from_str::<serde_json::Value>(&strings) {
+      Ok(x) => x,
+      Err(_) => bail!("Failed to parse playurl response: {}", &strings),
+    };
+
+    let data = json
+      .get("data")
+      .ok_or_else(|| anyhow!("Failed to parse .data"))?;
+
+    if let Some(video_reps) = data.pointer("/dash/video").and_then(|x| x.as_array()) {
+      let mut media = select_dash_representation(video_reps, quality)?;
+      media.audio_url = data
+        .pointer("/dash/audio")
+        .and_then(|x| x.as_array())
+        .and_then(|reps| select_dash_audio(reps));
+      return Ok(media);
+    }
+
+    let served_quality = data
+      .get("quality")
+      .and_then(|x| x.as_u64())
+      .map(|x| x as u32)
+      .unwrap_or(quality);
+    let url = data["durl"][0]["url"]
+      .as_str()
+      .ok_or_else(|| anyhow!("Failed to parse .data.durl[0].url"))?
+      .to_string();
+    Ok(ResolvedMedia {
+      url,
+      quality: served_quality,
+      quality_fallback: served_quality != quality,
+      // `durl` is the legacy progressive format: audio is already muxed into `url`.
+      audio_url: None,
+    })
+  }
+}
+
+/// Picks the DASH video representation closest to (but not exceeding) `quality`, falling
+/// back to the lowest available representation if the request asked for less than everything
+/// on offer.
+fn select_dash_representation(reps: &[serde_json::Value], quality: u32) -> anyhow::Result<ResolvedMedia> {
+  let mut best: Option<(u32, &str)> = None;
+  let mut lowest: Option<(u32, &str)> = None;
+
+  for rep in reps {
+    let id = match rep.get("id").and_then(|x| x.as_u64()) {
+      Some(id) => id as u32,
+      None => continue,
+    };
+    let base_url = match rep
+      .get("baseUrl")
+      .or_else(|| rep.get("base_url"))
+      .and_then(|x| x.as_str())
+    {
+      Some(base_url) => base_url,
+      None => continue,
+    };
+
+    if lowest.is_none_or(|(lowest_id, _)| id < lowest_id) {
+      lowest = Some((id, base_url));
+    }
+    if id <= quality && best.is_none_or(|(best_id, _)| id > best_id) {
+      best = Some((id, base_url));
+    }
+  }
+
+  let (served_quality, url) = best.or(lowest).ok_or_else(|| anyhow!("No usable DASH video representation"))?;
+  Ok(ResolvedMedia {
+    url: url.to_string(),
+    quality: served_quality,
+    quality_fallback: served_quality != quality,
+    audio_url: None,
+  })
+}
+
+/// Picks the highest-bitrate DASH audio representation on offer. Unlike video there's no
+/// user-facing quality knob for audio, so we just take the best available track.
+fn select_dash_audio(reps: &[serde_json::Value]) -> Option<String> {
+  reps
+    .iter()
+    .filter_map(|rep| {
+      let id = rep.get("id").and_then(|x| x.as_u64())?;
+      let base_url = rep
+        .get("baseUrl")
+        .or_else(|| rep.get("base_url"))
+        .and_then(|x| x.as_str())?;
+      Some((id, base_url))
+    })
+    .max_by_key(|(id, _)| *id)
+    .map(|(_, base_url)| base_url.to_string())
+}
+
+#[async_trait]
+impl VideoResolver for BilibiliResolver {
+  fn can_resolve(&self, id: &str) -> bool {
+    // `id.get(..2)` returns `None` both when the id is too short and when byte index 2 isn't
+    // a char boundary, so a multi-byte-prefixed id is rejected instead of panicking.
+    id.get(..2).is_some_and(|prefix| prefix.eq_ignore_ascii_case("bv"))
+  }
+
+  async fn resolve(&self, id: &str, page: usize, quality: u32) -> anyhow::Result<ResolvedMedia> {
+    let quality = nearest_quality_code(quality);
+    let cid_key = (id.to_string(), page);
+    let playurl_key = (id.to_string(), page, quality);
+
+    if let Some(media) = self.playurl_cache.get(&playurl_key).await {
+      return Ok(media);
+    }
+
+    let cid = if let Some(cid) = self.cid_cache.get(&cid_key).await {
+      cid.to_string()
+    } else {
+      let cid = self.resolve_cid(id, page).await?;
+      self.cid_cache.insert(cid_key, cid.parse::<u64>()?).await;
+      cid
+    };
+
+    let media = self.fetch_playurl(id, &cid, quality).await?;
+    self.playurl_cache.insert(playurl_key, media.clone()).await;
+    Ok(media)
+  }
+}
+
+/// Snaps an arbitrary `qn` value down to the nearest documented quality code, so callers
+/// passing an out-of-band number still get a sane result instead of an upstream error.
+/// Values below the lowest code (16) map to that lowest code.
+fn nearest_quality_code(quality: u32) -> u32 {
+  QUALITY_CODES
+    .iter()
+    .copied()
+    .filter(|&q| q <= quality)
+    .max()
+    .unwrap_or(QUALITY_CODES[0])
+}
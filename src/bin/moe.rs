@@ -1,11 +1,37 @@
-use anyhow::{anyhow, bail};
-use axum::extract::Path;
-use axum::http::StatusCode;
+use anyhow::anyhow;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::{Router, response::Html, routing::get};
 use clap::Parser;
 use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
 use w_kiva_moe::AppOpts;
+use w_kiva_moe::auth::{ApiKey, KeyStore};
+use w_kiva_moe::bilibili::BilibiliResolver;
+use w_kiva_moe::http_client;
+use w_kiva_moe::persistence::{JsonFileStore, SlotStore};
+use w_kiva_moe::resolver::ResolverRegistry;
+use w_kiva_moe::video_gw::{self, VideoGateway};
+
+// https://www.bilibili.com/opus/400555526268551002
+// quality 120 = 4K
+// quality 116 = 1080P60
+// quality 112 = 1080P+
+// quality 80 = 1080P
+// quality 74 = 720P60
+// quality 64 = 720P
+// quality 32 = 480P
+// quality 16 = 360P
+const DEFAULT_QUALITY: u32 = 116;
+
+#[derive(Clone)]
+struct AppState {
+  registry: Arc<ResolverRegistry>,
+  http_client: reqwest::Client,
+}
 
 // Make our own error that wraps `anyhow::Error`.
 struct AppError(anyhow::Error);
@@ -31,99 +57,100 @@ where
 }
 
 #[derive(Deserialize)]
-struct BvResolverParam {
-  pub bvid: String,
+struct VideoIdParam {
+  pub id: String,
   pub p: Option<usize>,
 }
 
-async fn bv_resolver(bv: String, p: usize) -> anyhow::Result<Redirect> {
-  let client = reqwest::Client::builder()
-    .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36")
-    .build()
-    .map_err(|e| anyhow!(e))?;
-
-  let cid = match client.get(format!(
-    "https://api.bilibili.com/x/player/pagelist?bvid={}",
-    bv
-  ))
-    .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7")
-    .header("Accept-Language", "zh-CN,zh;q=0.9")
-    .header("Cache-Control", "no-cache")
-    .header("DNT", "1")
-    .header("Pragma", "no-cache")
-    .header("Priority", "u=0, i")
-    .header("Sec-Fetch-Dest", "document")
-    .header("Sec-Fetch-Mode", "navigate")
-    .header("Sec-Fetch-Site", "none")
-    .header("Sec-Fetch-User", "?1")
-    .header("Upgrade-Insecure-Requests", "1")
-    .send().await {
-    Ok(x) => x,
-    Err(e) => bail!("Failed to get cid: {}", e),
-  };
-  let strings = match cid.text().await {
-    Ok(x) => x,
-    Err(e) => bail!("Failed to parse cid response as UTF8: {}", e),
-  };
-  let cid = match serde_json::from_str::<serde_json::Value>(&strings) {
-    Ok(x) => x,
-    Err(_) => bail!("Failed to parse cid response: {}", &strings),
+#[derive(Deserialize)]
+struct ResolveQuery {
+  pub proxy: Option<u8>,
+  pub quality: Option<u32>,
+}
+
+// Bilibili's CDN rejects requests that don't carry a matching Referer/UA, so we can't just
+// hand the raw URL to the client. This fetches the video ourselves and streams the body back,
+// forwarding Range so seeking still works in players that request partial content.
+async fn proxy_stream(
+  client: &reqwest::Client,
+  url: String,
+  range: Option<HeaderValue>,
+) -> anyhow::Result<Response> {
+  let mut req = client.get(&url).header("Referer", "https://www.bilibili.com");
+  if let Some(range) = range {
+    req = req.header(header::RANGE, range);
+  }
+
+  let upstream = req
+    .send()
+    .await
+    .map_err(|e| anyhow!("Failed to fetch upstream video: {}", e))?;
+
+  // Forward the upstream status as-is (200/206 on success, but also 403/404/410/5xx on
+  // failure, e.g. an expired signed CDN URL) instead of coercing everything to 200.
+  let status = upstream.status();
+
+  let mut headers = HeaderMap::new();
+  for name in [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+  ] {
+    if let Some(value) = upstream.headers().get(&name) {
+      headers.insert(name, value.clone());
+    }
+  }
+
+  let body = Body::from_stream(upstream.bytes_stream());
+  Ok((status, headers, body).into_response())
+}
+
+async fn resolve_handler(
+  state: AppState,
+  id: String,
+  p: usize,
+  quality: u32,
+  proxy: bool,
+  range: Option<HeaderValue>,
+) -> Result<Response, AppError> {
+  let Some(resolver) = state.registry.resolver_for(&id) else {
+    return Ok((StatusCode::NOT_FOUND, format!("No resolver for id: {}", id)).into_response());
   };
 
-  let cid = match cid
-    .as_object()
-    .and_then(|x| x.get("data"))
-    .and_then(|x| x.as_array())
-    .and_then(|x| x.get(p - 1))
-    .and_then(|x| x.as_object())
-    .and_then(|x| x.get("cid"))
-    .and_then(|x| x.as_number())
+  let media = resolver.resolve(&id, p, quality).await?;
+
+  let mut headers = HeaderMap::new();
+  headers.insert("X-Resolved-Quality", HeaderValue::from(media.quality));
+  headers.insert(
+    "X-Quality-Fallback",
+    HeaderValue::from_static(if media.quality_fallback { "1" } else { "0" }),
+  );
+  // Set when the resolver served a video-only DASH representation, so a player knows it still
+  // needs to fetch and mux a separate audio track. `proxy` mode streams only the video URL and
+  // passes this header through.
+  if let Some(audio_url) = &media.audio_url
+    && let Ok(value) = HeaderValue::from_str(audio_url)
   {
-    Some(x) => x.to_string(),
-    None => bail!("Failed to get cid from response: {}", cid),
-  };
-  // https://www.bilibili.com/opus/400555526268551002
-  // quality 120 = 4K
-  // quality 116 = 1080P60
-  // quality 112 = 1080P+
-  // quality 80 = 1080P
-  // quality 74 = 720P60
-  // quality 64 = 720P
-  // quality 32 = 480P
-  // quality 16 = 360P
-  let quality = 116;
-  let playurl = match client.get(format!(
-    "https://api.bilibili.com/x/player/playurl?bvid={}&cid={}&qn={}&type=&otype=json&platform=html5&high_quality=1",
-    bv, cid, quality,
-  ))
-    .header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7")
-    .header("Accept-Language", "zh-CN,zh;q=0.9")
-    .header("Cache-Control", "no-cache")
-    .header("DNT", "1")
-    .header("Pragma", "no-cache")
-    .header("Priority", "u=0, i")
-    .header("Sec-Fetch-Dest", "document")
-    .header("Sec-Fetch-Mode", "navigate")
-    .header("Sec-Fetch-Site", "none")
-    .header("Sec-Fetch-User", "?1")
-    .header("Upgrade-Insecure-Requests", "1")
-    .send().await {
-    Ok(x) => x,
-    Err(e) => bail!("Failed to get playurl: {}", e),
-  };
-  let strings = match playurl.text().await {
-    Ok(x) => x,
-    Err(e) => bail!("Failed to parse playurl response as UTF8: {}", e),
-  };
-  let json = match serde_json::from_str::<serde_json::Value>(&strings) {
-    Ok(x) => x,
-    Err(_) => bail!("Failed to parse playurl response: {}", &strings),
+    headers.insert("X-Audio-Url", value);
+  }
+
+  if proxy {
+    Ok((headers, proxy_stream(&state.http_client, media.url, range).await?).into_response())
+  } else {
+    Ok((headers, Redirect::temporary(media.url.as_str())).into_response())
+  }
+}
+
+/// Loads the `/redirect` management API's key list from `path`, if configured. Left unset,
+/// an empty [`KeyStore`] is used and none of those routes can be authorized.
+fn load_api_keys(path: Option<&str>) -> anyhow::Result<Vec<ApiKey>> {
+  let Some(path) = path else {
+    return Ok(Vec::new());
   };
-  let url = json["data"]["durl"][0]["url"]
-    .as_str()
-    .ok_or_else(|| anyhow!("Failed to parse .data.durl[0].url"))?
-    .to_string();
-  Ok(Redirect::temporary(url.as_str()))
+  let data = std::fs::read_to_string(path)
+    .map_err(|e| anyhow!("Failed to read api keys file {}: {}", path, e))?;
+  serde_json::from_str(&data).map_err(|e| anyhow!("Failed to parse api keys file {}: {}", path, e))
 }
 
 #[tokio::main]
@@ -139,29 +166,57 @@ async fn main() {
 
   let opts = AppOpts::parse();
 
+  let http_client = http_client::build_client().expect("failed to build shared HTTP client");
+
+  let mut registry = ResolverRegistry::new();
+  registry.register(Arc::new(BilibiliResolver::new(http_client.clone())));
+  let state = AppState {
+    registry: Arc::new(registry),
+    http_client,
+  };
+
+  let api_keys = load_api_keys(opts.redirect_api_keys_file.as_deref())
+    .expect("failed to load redirect api keys file");
+  let key_store = Arc::new(KeyStore::new(api_keys));
+
+  let slot_store: Arc<dyn SlotStore> = Arc::new(JsonFileStore::new(opts.redirect_store_path));
+  let gateway = VideoGateway::new(
+    opts.redirect_max_slots,
+    Duration::from_secs(opts.redirect_ttl_secs),
+    slot_store,
+  )
+  .await
+  .expect("failed to initialize video gateway");
+
   // build our application with a route
   let app = Router::new()
     .route("/", get(async move || Html("Hello from W")))
     .route("/health", get(async move || Html("OK")))
     .route(
-      "/{bvid}",
+      "/{id}",
       get(
-        async move |params: Path<BvResolverParam>| -> Result<Redirect, AppError> {
-          Ok(bv_resolver(params.bvid.clone(), 1).await?)
+        async move |State(state): State<AppState>, params: Path<VideoIdParam>, Query(q): Query<ResolveQuery>, headers: HeaderMap| -> Result<Response, AppError> {
+          let range = headers.get(header::RANGE).cloned();
+          let quality = q.quality.unwrap_or(DEFAULT_QUALITY);
+          resolve_handler(state, params.id.clone(), 1, quality, q.proxy.unwrap_or(0) != 0, range).await
         },
       ),
     )
     .route(
-      "/{bvid}/{p}",
+      "/{id}/{p}",
       get(
-        async move |params: Path<BvResolverParam>| -> Result<Redirect, AppError> {
+        async move |State(state): State<AppState>, params: Path<VideoIdParam>, Query(q): Query<ResolveQuery>, headers: HeaderMap| -> Result<Response, AppError> {
           let p = params
             .p
             .unwrap_or(1usize);
-          Ok(bv_resolver(params.bvid.clone(), p).await?)
+          let range = headers.get(header::RANGE).cloned();
+          let quality = q.quality.unwrap_or(DEFAULT_QUALITY);
+          resolve_handler(state, params.id.clone(), p, quality, q.proxy.unwrap_or(0) != 0, range).await
         },
       ),
-    );
+    )
+    .with_state(state)
+    .merge(video_gw::router(gateway, key_store));
 
   // run it
   let listener = tokio::net::TcpListener::bind(opts.listen).await.unwrap();
@@ -0,0 +1,101 @@
+use anyhow::anyhow;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const UPSTREAM_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const BASE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Builds the single shared client every upstream call should go through, so connections
+/// (and the TLS handshake) get reused instead of being redone on every request.
+pub fn build_client() -> anyhow::Result<reqwest::Client> {
+  reqwest::Client::builder()
+    .user_agent(UPSTREAM_USER_AGENT)
+    .build()
+    .map_err(|e| anyhow!(e))
+}
+
+/// Distinguishes failures worth retrying (connection resets, timeouts, 5xx, upstream rate
+/// limiting) from ones that won't get better on a retry (4xx, malformed responses).
+pub enum FetchError {
+  Retryable(anyhow::Error),
+  Fatal(anyhow::Error),
+}
+
+/// Retries `attempt` up to `max_attempts` times with jittered exponential backoff, stopping
+/// immediately on a [`FetchError::Fatal`].
+pub async fn retry_with_backoff<T, F, Fut>(max_attempts: u32, mut attempt: F) -> anyhow::Result<T>
+where
+  F: FnMut() -> Fut,
+  Fut: Future<Output = Result<T, FetchError>>,
+{
+  let mut last_err = None;
+  for attempt_no in 0..max_attempts.max(1) {
+    match attempt().await {
+      Ok(value) => return Ok(value),
+      Err(FetchError::Fatal(e)) => return Err(e),
+      Err(FetchError::Retryable(e)) => {
+        log::warn!("Upstream call failed (attempt {}/{}): {}", attempt_no + 1, max_attempts, e);
+        last_err = Some(e);
+        if attempt_no + 1 < max_attempts {
+          tokio::time::sleep(backoff_delay(attempt_no)).await;
+        }
+      }
+    }
+  }
+  Err(last_err.unwrap_or_else(|| anyhow!("retry loop exited without making an attempt")))
+}
+
+fn backoff_delay(attempt_no: u32) -> Duration {
+  let jitter_ms = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.subsec_millis() % 100)
+    .unwrap_or(0);
+  BASE_BACKOFF * 2u32.pow(attempt_no) + Duration::from_millis(jitter_ms as u64)
+}
+
+/// GETs `url` with the given headers, retrying transient failures and treating Bilibili's
+/// `code: -412` rate-limit signal (which rides along on an HTTP 200) as retryable too.
+pub async fn get_text_with_retry(
+  client: &reqwest::Client,
+  url: &str,
+  headers: &[(&str, &str)],
+) -> anyhow::Result<String> {
+  retry_with_backoff(DEFAULT_MAX_ATTEMPTS, || async {
+    let mut req = client.get(url);
+    for (name, value) in headers {
+      req = req.header(*name, *value);
+    }
+
+    let resp = match req.send().await {
+      Ok(resp) => resp,
+      Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+        return Err(FetchError::Retryable(anyhow!(e)));
+      }
+      Err(e) => return Err(FetchError::Fatal(anyhow!(e))),
+    };
+
+    let status = resp.status();
+    if status.is_server_error() {
+      return Err(FetchError::Retryable(anyhow!("upstream returned {}", status)));
+    }
+    if status.is_client_error() {
+      return Err(FetchError::Fatal(anyhow!("upstream returned {}", status)));
+    }
+
+    let text = match resp.text().await {
+      Ok(text) => text,
+      Err(e) => return Err(FetchError::Fatal(anyhow!("failed to read response body: {}", e))),
+    };
+
+    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
+      && json.get("code").and_then(|c| c.as_i64()) == Some(-412)
+    {
+      return Err(FetchError::Retryable(anyhow!("rate-limited by upstream (code -412)")));
+    }
+
+    Ok(text)
+  })
+  .await
+}
@@ -4,6 +4,28 @@ use clap::Parser;
 pub struct AppOpts {
   #[clap(short = 'l', long, env, default_value = "0.0.0.0:9980")]
   pub listen: String,
+
+  /// Path to the JSON file backing the redirect slot store, see [`crate::persistence::JsonFileStore`].
+  #[clap(long, env, default_value = "data/redirects.json")]
+  pub redirect_store_path: String,
+
+  /// Maximum number of concurrently live redirect slots.
+  #[clap(long, env, default_value = "64")]
+  pub redirect_max_slots: u32,
+
+  /// How long a freshly created or touched redirect slot stays alive.
+  #[clap(long, env, default_value = "3600")]
+  pub redirect_ttl_secs: u64,
+
+  /// Path to a JSON file containing the `Vec<ApiKey>` that gates `/redirect` management
+  /// routes, see [`crate::auth::ApiKey`]. Left unset, no key can authorize those routes.
+  #[clap(long, env)]
+  pub redirect_api_keys_file: Option<String>,
 }
 
+pub mod auth;
+pub mod bilibili;
+pub mod http_client;
+pub mod persistence;
+pub mod resolver;
 pub mod video_gw;
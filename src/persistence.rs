@@ -0,0 +1,111 @@
+use crate::video_gw::SlotId;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// A backend that remembers `slot_id -> (url, expires_at)` across restarts, so redirect
+/// links handed out by [`crate::video_gw::VideoGateway`] stay valid across a deploy instead
+/// of resetting every time the process restarts.
+#[async_trait]
+pub trait SlotStore: Send + Sync {
+  async fn load_all(&self) -> anyhow::Result<Vec<(SlotId, String, SystemTime)>>;
+  async fn put(&self, slot_id: SlotId, url: &str, expires_at: SystemTime) -> anyhow::Result<()>;
+  async fn remove(&self, slot_id: SlotId) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredEntry {
+  url: String,
+  expires_at_unix: u64,
+}
+
+/// The simplest persistence backend: the whole slot map as a single JSON file, read-modified
+/// and rewritten on every write. Fine at the scale a `max_slots`-bounded gateway operates at.
+pub struct JsonFileStore {
+  path: PathBuf,
+  write_lock: Mutex<()>,
+}
+
+impl JsonFileStore {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      write_lock: Mutex::new(()),
+    }
+  }
+
+  fn read_sync(path: &Path) -> anyhow::Result<HashMap<SlotId, StoredEntry>> {
+    match std::fs::read_to_string(path) {
+      Ok(data) => serde_json::from_str(&data)
+        .map_err(|e| anyhow!("Persisted slot store at {} is corrupt: {}", path.display(), e)),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Writes `map` to a sibling temp file and renames it into place, so a crash or kill mid-write
+  /// can never leave `path` truncated or half-written.
+  fn write_sync(path: &Path, map: &HashMap<SlotId, StoredEntry>) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(OsString::from(".tmp"));
+    let tmp_path = PathBuf::from(tmp_name);
+    std::fs::write(&tmp_path, serde_json::to_string_pretty(map)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl SlotStore for JsonFileStore {
+  async fn load_all(&self) -> anyhow::Result<Vec<(SlotId, String, SystemTime)>> {
+    let path = self.path.clone();
+    let map = tokio::task::spawn_blocking(move || Self::read_sync(&path)).await??;
+    Ok(
+      map
+        .into_iter()
+        .map(|(slot_id, entry)| {
+          (
+            slot_id,
+            entry.url,
+            UNIX_EPOCH + Duration::from_secs(entry.expires_at_unix),
+          )
+        })
+        .collect(),
+    )
+  }
+
+  async fn put(&self, slot_id: SlotId, url: &str, expires_at: SystemTime) -> anyhow::Result<()> {
+    let _guard = self.write_lock.lock().await;
+    let path = self.path.clone();
+    let url = url.to_string();
+    let expires_at_unix = expires_at
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_secs();
+    tokio::task::spawn_blocking(move || {
+      let mut map = Self::read_sync(&path)?;
+      map.insert(slot_id, StoredEntry { url, expires_at_unix });
+      Self::write_sync(&path, &map)
+    })
+    .await?
+  }
+
+  async fn remove(&self, slot_id: SlotId) -> anyhow::Result<()> {
+    let _guard = self.write_lock.lock().await;
+    let path = self.path.clone();
+    tokio::task::spawn_blocking(move || {
+      let mut map = Self::read_sync(&path)?;
+      map.remove(&slot_id);
+      Self::write_sync(&path, &map)
+    })
+    .await?
+  }
+}
@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// The outcome of resolving a video id: the playable URL plus what quality was actually
+/// served, since a resolver may have to fall back when the requested quality isn't available.
+#[derive(Debug, Clone)]
+pub struct ResolvedMedia {
+  pub url: String,
+  pub quality: u32,
+  pub quality_fallback: bool,
+  /// The DASH audio track to mux alongside `url`, when the resolver served a video-only DASH
+  /// representation rather than a muxed progressive stream.
+  pub audio_url: Option<String>,
+}
+
+/// A backend capable of turning a platform-specific video id into a playable media URL.
+///
+/// Each resolver claims the ids it understands via [`VideoResolver::can_resolve`]; the
+/// [`ResolverRegistry`] consults that to route a request without the HTTP layer needing to
+/// know which platform (or how many of them) are actually wired up.
+#[async_trait]
+pub trait VideoResolver: Send + Sync {
+  /// Returns `true` if this resolver can handle the given id.
+  fn can_resolve(&self, id: &str) -> bool;
+
+  /// Resolves `id`/`page` at the given `quality` to a playable media URL.
+  async fn resolve(&self, id: &str, page: usize, quality: u32) -> anyhow::Result<ResolvedMedia>;
+}
+
+/// Dispatches a video id to whichever registered [`VideoResolver`] claims it.
+#[derive(Clone, Default)]
+pub struct ResolverRegistry {
+  resolvers: Vec<Arc<dyn VideoResolver>>,
+}
+
+impl ResolverRegistry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn register(&mut self, resolver: Arc<dyn VideoResolver>) {
+    self.resolvers.push(resolver);
+  }
+
+  /// Returns the first registered resolver that claims `id`, if any.
+  pub fn resolver_for(&self, id: &str) -> Option<Arc<dyn VideoResolver>> {
+    self.resolvers.iter().find(|r| r.can_resolve(id)).cloned()
+  }
+}
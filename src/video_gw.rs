@@ -3,25 +3,56 @@ use axum::{
   Router,
   extract::{Path, State},
   http::StatusCode,
+  middleware::from_fn_with_state,
   routing::{get, post},
 };
 use moka::future::Cache;
+use moka::Expiry;
 use serde::{Deserialize, Serialize};
 use std::{
-  collections::VecDeque,
+  collections::{HashSet, VecDeque},
   sync::Arc,
-  time::Duration,
+  time::{Duration, Instant, SystemTime},
 };
 use axum::response::Redirect;
 use moka::notification::RemovalCause;
 use tokio::sync::Mutex;
 
+use crate::auth::{KeyStore, Scope, ScopedAuth, require_scope};
+use crate::persistence::SlotStore;
+
 pub type SlotId = u32;
 
+#[derive(Clone)]
+struct CacheEntry {
+  url: String,
+  expires_at: SystemTime,
+}
+
+struct SlotExpiry;
+
+impl Expiry<SlotId, CacheEntry> for SlotExpiry {
+  fn expire_after_create(
+    &self,
+    _key: &SlotId,
+    value: &CacheEntry,
+    _created_at: Instant,
+  ) -> Option<Duration> {
+    Some(
+      value
+        .expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO),
+    )
+  }
+}
+
 #[derive(Clone)]
 pub struct VideoGateway {
-  cache: Cache<SlotId, String>,
+  cache: Cache<SlotId, CacheEntry>,
   free_slots: Arc<Mutex<VecDeque<SlotId>>>,
+  store: Arc<dyn SlotStore>,
+  default_ttl: Duration,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,13 +62,31 @@ pub struct RedirectEntry {
 }
 
 impl VideoGateway {
-  pub fn new(max_slots: SlotId, ttl: Duration) -> Arc<Self> {
-    let free_slots = Arc::new(Mutex::new((1..=max_slots).collect::<VecDeque<_>>()));
+  pub async fn new(
+    max_slots: SlotId,
+    ttl: Duration,
+    store: Arc<dyn SlotStore>,
+  ) -> anyhow::Result<Arc<Self>> {
+    let now = SystemTime::now();
+    let live_entries: Vec<_> = store
+      .load_all()
+      .await?
+      .into_iter()
+      .filter(|(slot_id, _, expires_at)| (1..=max_slots).contains(slot_id) && *expires_at > now)
+      .collect();
+
+    let occupied: HashSet<SlotId> = live_entries.iter().map(|(slot_id, ..)| *slot_id).collect();
+    let free_slots = Arc::new(Mutex::new(
+      (1..=max_slots)
+        .filter(|slot_id| !occupied.contains(slot_id))
+        .collect::<VecDeque<_>>(),
+    ));
     let free_slots_for_eviction = free_slots.clone();
+    let store_for_eviction = store.clone();
 
     let cache = Cache::builder()
       .max_capacity(max_slots as u64)
-      .time_to_live(ttl)
+      .expire_after(SlotExpiry)
       .eviction_listener(move |slot_id, _value, cause| {
         match cause {
           RemovalCause::Replaced => return,
@@ -45,18 +94,30 @@ impl VideoGateway {
           RemovalCause::Explicit => {}
           RemovalCause::Size => {}
         }
+        let slot_id = *slot_id;
         let free_slots_for_eviction = free_slots_for_eviction.clone();
+        let store_for_eviction = store_for_eviction.clone();
         tokio::spawn(async move {
           let mut guard = free_slots_for_eviction.lock().await;
-          guard.push_back(*slot_id);
+          guard.push_back(slot_id);
+          drop(guard);
+          if let Err(e) = store_for_eviction.remove(slot_id).await {
+            log::warn!("Failed to remove slot {} from persistent store: {}", slot_id, e);
+          }
           log::info!("Slot {} evicted", slot_id);
         });
       })
       .build();
 
+    for (slot_id, url, expires_at) in live_entries {
+      cache.insert(slot_id, CacheEntry { url, expires_at }).await;
+    }
+
     let sv = Arc::new(Self {
       cache,
       free_slots,
+      store,
+      default_ttl: ttl,
     });
 
     {
@@ -69,7 +130,7 @@ impl VideoGateway {
       });
     }
 
-    sv
+    Ok(sv)
   }
 
   pub async fn tick(&self) {
@@ -81,24 +142,42 @@ impl VideoGateway {
     let slot_id = guard.pop_front()?;
     drop(guard);
 
-    self.cache.insert(slot_id, url.clone()).await;
+    let expires_at = SystemTime::now() + self.default_ttl;
+    self
+      .cache
+      .insert(slot_id, CacheEntry { url: url.clone(), expires_at })
+      .await;
+    if let Err(e) = self.store.put(slot_id, &url, expires_at).await {
+      log::warn!("Failed to persist slot {}: {}", slot_id, e);
+    }
     log::info!("Slot {} created, {}", slot_id, url);
     Some(slot_id)
   }
 
   pub async fn touch_redirect_slot(&self, slot_id: SlotId) {
-    if let Some(url) = self.cache.get(&slot_id).await {
-      self.cache.insert(slot_id, url.clone()).await;
-      log::info!("Slot {} touched, {}", slot_id, url);
+    if let Some(entry) = self.cache.get(&slot_id).await {
+      let expires_at = SystemTime::now() + self.default_ttl;
+      self
+        .cache
+        .insert(slot_id, CacheEntry { url: entry.url.clone(), expires_at })
+        .await;
+      if let Err(e) = self.store.put(slot_id, &entry.url, expires_at).await {
+        log::warn!("Failed to persist touch for slot {}: {}", slot_id, e);
+      }
+      log::info!("Slot {} touched, {}", slot_id, entry.url);
     }
   }
 
   pub async fn get_redirect(&self, slot_id: SlotId) -> Option<String> {
-    self.cache.get(&slot_id).await
+    self.cache.get(&slot_id).await.map(|entry| entry.url)
   }
 
   pub async fn get_all_redirect(&self) -> Option<Vec<RedirectEntry>> {
-    let map = self.cache.iter().map(|(slot_id, url)| RedirectEntry { slot_id: *slot_id, url: url.clone() }).collect();
+    let map = self
+      .cache
+      .iter()
+      .map(|(slot_id, entry)| RedirectEntry { slot_id: *slot_id, url: entry.url.clone() })
+      .collect();
     Some(map)
   }
 }
@@ -167,11 +246,32 @@ pub async fn batch_touch_redirect_handler(
   Ok(StatusCode::OK)
 }
 
-pub fn router(gateway: Arc<VideoGateway>) -> Router {
+pub fn router(gateway: Arc<VideoGateway>, keys: Arc<KeyStore>) -> Router {
+  let scoped_auth = |scope: Scope| ScopedAuth { store: keys.clone(), scope };
+
   Router::new()
-    .route("/redirect", post(create_redirect_handler))
-    .route("/redirect", get(get_all_redirect_handler))
+    .route(
+      "/redirect",
+      post(create_redirect_handler).route_layer(from_fn_with_state(
+        scoped_auth(Scope::Create),
+        require_scope,
+      )),
+    )
+    .route(
+      "/redirect",
+      get(get_all_redirect_handler).route_layer(from_fn_with_state(
+        scoped_auth(Scope::Read),
+        require_scope,
+      )),
+    )
+    // The public redirect itself stays open: this is the link we hand out to end users.
     .route("/redirect/{slot_id}", get(get_redirect_handler))
-    .route("/redirect/touch", post(batch_touch_redirect_handler))
+    .route(
+      "/redirect/touch",
+      post(batch_touch_redirect_handler).route_layer(from_fn_with_state(
+        scoped_auth(Scope::Touch),
+        require_scope,
+      )),
+    )
     .with_state(gateway)
 }